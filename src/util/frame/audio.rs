@@ -1,19 +1,35 @@
 use std::mem;
+use std::ptr;
 use std::slice;
+use std::cell::RefCell;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
-use libc::{c_int, int64_t, c_ulonglong};
+use libc::{c_int, int64_t, c_ulonglong, c_void};
 use ffi::*;
+use ::Error;
 use ::ChannelLayout;
 use ::util::format;
 use super::Frame;
 
-#[derive(PartialEq, Eq)]
-pub struct Audio(Frame);
+type ConverterKey = (format::Sample, u32, ChannelLayout, format::Sample, u32, ChannelLayout);
+
+struct Converter {
+	context: *mut SwrContext,
+	key:     Option<ConverterKey>,
+}
+
+impl Converter {
+	fn new() -> Self {
+		Converter { context: ptr::null_mut(), key: None }
+	}
+}
+
+pub struct Audio(Frame, RefCell<Converter>);
 
 impl Audio {
 	pub unsafe fn wrap(ptr: *mut AVFrame) -> Self {
-		Audio(Frame::wrap(ptr))
+		Audio(Frame::wrap(ptr), RefCell::new(Converter::new()))
 	}
 
 	pub unsafe fn alloc(&mut self, format: format::Sample, samples: usize, layout: ChannelLayout) {
@@ -28,7 +44,7 @@ impl Audio {
 impl Audio {
 	pub fn empty() -> Self {
 		unsafe {
-			Audio(Frame::empty())
+			Audio(Frame::empty(), RefCell::new(Converter::new()))
 		}
 	}
 
@@ -114,7 +130,8 @@ impl Audio {
 		self.format().is_packed()
 	}
 
-	pub fn planes(&self) -> usize {
+	#[doc(alias = "planes")]
+	pub fn plane_count(&self) -> usize {
 		unsafe {
 			if (*self.as_ptr()).linesize[0] == 0 {
 				return 0;
@@ -129,8 +146,51 @@ impl Audio {
 		}
 	}
 
+	pub fn planes<T: Sample>(&self) -> Vec<&[T]> {
+		if !<T as Sample>::is_valid(self.format()) {
+			panic!("unsupported type");
+		}
+
+		let mut result = Vec::new();
+
+		for i in 0 .. self.plane_count() {
+			result.push(self.plane(i));
+		}
+
+		result
+	}
+
+	pub fn planes_mut<T: Sample>(&mut self) -> Vec<&mut [T]> {
+		if !<T as Sample>::is_valid(self.format()) {
+			panic!("unsupported type");
+		}
+
+		let mut result = Vec::new();
+
+		let length = self.plane_samples();
+
+		unsafe {
+			for i in 0 .. self.plane_count() {
+				result.push(slice::from_raw_parts_mut(
+					mem::transmute((*self.as_mut_ptr()).data[i]),
+					length));
+			}
+		}
+
+		result
+	}
+
+	fn plane_samples(&self) -> usize {
+		if self.is_packed() {
+			self.samples() * self.channels() as usize
+		}
+		else {
+			self.samples()
+		}
+	}
+
 	pub fn plane<T: Sample>(&self, index: usize) -> &[T] {
-		if index >= self.planes() {
+		if index >= self.plane_count() {
 			panic!("out of bounds");
 		}
 
@@ -139,14 +199,18 @@ impl Audio {
 		}
 
 		unsafe {
+			if (*self.as_ptr()).linesize[0] == 0 {
+				return &[];
+			}
+
 			slice::from_raw_parts(
 				mem::transmute((*self.as_ptr()).data[index]),
-				mem::size_of::<T>() * self.samples())
+				self.plane_samples())
 		}
 	}
 
-	pub fn plane_mut<T: Sample>(&mut self, index: usize) -> &[T] {
-		if index >= self.planes() {
+	pub fn plane_mut<T: Sample>(&mut self, index: usize) -> &mut [T] {
+		if index >= self.plane_count() {
 			panic!("out of bounds");
 		}
 
@@ -154,10 +218,219 @@ impl Audio {
 			panic!("unsupported type");
 		}
 
+		let length = self.plane_samples();
+
 		unsafe {
+			if (*self.as_mut_ptr()).linesize[0] == 0 {
+				return &mut [];
+			}
+
 			slice::from_raw_parts_mut(
 				mem::transmute((*self.as_mut_ptr()).data[index]),
-				mem::size_of::<T>() * self.samples())
+				length)
+		}
+	}
+
+	pub fn channel<T: Sample>(&self, channel: usize) -> &[T] {
+		if !self.is_planar() {
+			panic!("channel() is planar only; use channel_samples() for packed");
+		}
+
+		if channel >= self.channels() as usize {
+			panic!("out of bounds");
+		}
+
+		if !<T as Sample>::is_valid(self.format()) {
+			panic!("unsupported type");
+		}
+
+		unsafe {
+			if (*self.as_ptr()).linesize[0] == 0 {
+				return &[];
+			}
+
+			slice::from_raw_parts(
+				mem::transmute((*self.as_ptr()).data[channel]),
+				self.samples())
+		}
+	}
+
+	pub fn converted(&self, format: format::Sample, rate: u32, layout: ChannelLayout) -> Result<Audio, Error> {
+		unsafe {
+			let context = self.converter(format, rate, layout)?;
+
+			let delay       = swr_get_delay(context, self.rate() as int64_t);
+			let out_samples  = av_rescale_rnd(delay + self.samples() as int64_t,
+				rate as int64_t, self.rate() as int64_t, AVRounding::AV_ROUND_UP);
+
+			let mut output = Audio::new(format, out_samples as usize, layout);
+			output.set_rate(rate);
+
+			let count = swr_convert(context,
+				(*output.as_mut_ptr()).data.as_mut_ptr(), out_samples as c_int,
+				(*self.as_ptr()).data.as_ptr() as *const *const u8, self.samples() as c_int);
+
+			if count < 0 {
+				return Err(Error::from(count));
+			}
+
+			output.set_samples(count as usize);
+
+			Ok(output)
+		}
+	}
+
+	unsafe fn converter(&self, format: format::Sample, rate: u32, layout: ChannelLayout) -> Result<*mut SwrContext, Error> {
+		let key            = (self.format(), self.rate(), self.channel_layout(), format, rate, layout);
+		let mut converter  = self.1.borrow_mut();
+
+		if converter.key != Some(key) {
+			if !converter.context.is_null() {
+				swr_free(&mut converter.context);
+			}
+
+			let context = swr_alloc_set_opts(ptr::null_mut(),
+				layout.bits() as int64_t, format.into(), rate as c_int,
+				self.channel_layout().bits() as int64_t, self.format().into(), self.rate() as c_int,
+				0, ptr::null_mut::<c_void>());
+
+			if context.is_null() {
+				converter.key = None;
+				return Err(Error::InvalidData);
+			}
+
+			converter.context = context;
+			converter.key     = Some(key);
+		}
+
+		if swr_init(converter.context) < 0 {
+			return Err(Error::InvalidData);
+		}
+
+		Ok(converter.context)
+	}
+
+	pub fn frames<T: Sample>(&self) -> Result<Frames<T>, Error> {
+		if !<T as Sample>::is_valid(self.format()) {
+			panic!("unsupported type");
+		}
+
+		// A single planar frame spans every plane, so it cannot be handed back as
+		// one contiguous &[T]; only packed layouts are supported here.
+		if self.is_planar() {
+			return Err(Error::InvalidData);
+		}
+
+		Ok(Frames { frame: self, index: 0, _marker: PhantomData })
+	}
+
+	pub fn channel_samples<T: Sample>(&self, channel: usize) -> ChannelSamples<T> {
+		if !<T as Sample>::is_valid(self.format()) {
+			panic!("unsupported type");
+		}
+
+		if channel >= self.channels() as usize {
+			panic!("out of bounds");
+		}
+
+		ChannelSamples { frame: self, channel: channel, index: 0, _marker: PhantomData }
+	}
+
+	pub fn transform<T, F>(&mut self, mut f: F)
+		where T: Sample + Copy,
+		      F: FnMut(u16, usize, T) -> T
+	{
+		if !<T as Sample>::is_valid(self.format()) {
+			panic!("unsupported type");
+		}
+
+		let channels = self.channels() as usize;
+		let samples  = self.samples();
+		let planar   = self.is_planar();
+
+		unsafe {
+			for c in 0 .. channels {
+				let base: *mut T = if planar {
+					mem::transmute((*self.as_mut_ptr()).data[c])
+				}
+				else {
+					mem::transmute((*self.as_mut_ptr()).data[0])
+				};
+
+				for n in 0 .. samples {
+					let index = if planar { n } else { n * channels + c };
+
+					let value = *base.offset(index as isize);
+					*base.offset(index as isize) = f(c as u16, n, value);
+				}
+			}
+		}
+	}
+
+	pub fn to_packed(&self) -> Audio {
+		if self.is_packed() {
+			return self.clone();
+		}
+
+		let mut output = Audio::new(packed(self.format()), self.samples(), self.channel_layout());
+		output.set_rate(self.rate());
+
+		match self.format() {
+			format::Sample::U8(..)  => self.interleave::<u8>(&mut output),
+			format::Sample::I16(..) => self.interleave::<i16>(&mut output),
+			format::Sample::I32(..) => self.interleave::<i32>(&mut output),
+			format::Sample::F32(..) => self.interleave::<f32>(&mut output),
+			format::Sample::F64(..) => self.interleave::<f64>(&mut output),
+			format::Sample::None    => (),
+		}
+
+		output
+	}
+
+	pub fn to_planar(&self) -> Audio {
+		if self.is_planar() {
+			return self.clone();
+		}
+
+		let mut output = Audio::new(planar(self.format()), self.samples(), self.channel_layout());
+		output.set_rate(self.rate());
+
+		match self.format() {
+			format::Sample::U8(..)  => self.deinterleave::<u8>(&mut output),
+			format::Sample::I16(..) => self.deinterleave::<i16>(&mut output),
+			format::Sample::I32(..) => self.deinterleave::<i32>(&mut output),
+			format::Sample::F32(..) => self.deinterleave::<f32>(&mut output),
+			format::Sample::F64(..) => self.deinterleave::<f64>(&mut output),
+			format::Sample::None    => (),
+		}
+
+		output
+	}
+
+	fn interleave<T: Sample + Copy>(&self, output: &mut Audio) {
+		let channels = self.channels() as usize;
+		let samples  = self.samples();
+		let out      = output.plane_mut::<T>(0);
+
+		for c in 0 .. channels {
+			let input = self.channel::<T>(c);
+
+			for n in 0 .. samples {
+				out[n * channels + c] = input[n];
+			}
+		}
+	}
+
+	fn deinterleave<T: Sample + Copy>(&self, output: &mut Audio) {
+		let channels = self.channels() as usize;
+		let samples  = self.samples();
+		let input    = self.plane::<T>(0);
+		let mut out  = output.planes_mut::<T>();
+
+		for c in 0 .. channels {
+			for n in 0 .. samples {
+				out[c][n] = input[n * channels + c];
+			}
 		}
 	}
 
@@ -165,7 +438,7 @@ impl Audio {
 		let mut result = Vec::new();
 
 		unsafe {
-			for i in 0 .. self.planes() {
+			for i in 0 .. self.plane_count() {
 				result.push(slice::from_raw_parts(
 					(*self.as_ptr()).data[i],
 					(*self.as_ptr()).linesize[0] as usize));
@@ -179,7 +452,7 @@ impl Audio {
 		let mut result = Vec::new();
 
 		unsafe {
-			for i in 0 .. self.planes() {
+			for i in 0 .. self.plane_count() {
 				result.push(slice::from_raw_parts_mut(
 					(*self.as_mut_ptr()).data[i],
 					(*self.as_ptr()).linesize[0] as usize));
@@ -220,6 +493,117 @@ impl Clone for Audio {
 	}
 }
 
+impl PartialEq for Audio {
+	fn eq(&self, other: &Self) -> bool {
+		self.0 == other.0
+	}
+}
+
+impl Eq for Audio { }
+
+impl Drop for Audio {
+	fn drop(&mut self) {
+		unsafe {
+			let mut converter = self.1.borrow_mut();
+
+			if !converter.context.is_null() {
+				swr_free(&mut converter.context);
+			}
+		}
+	}
+}
+
+pub struct Frames<'a, T: Sample> {
+	frame:   &'a Audio,
+	index:   usize,
+	_marker: PhantomData<T>,
+}
+
+impl<'a, T: Sample> Iterator for Frames<'a, T> {
+	type Item = &'a [T];
+
+	fn next(&mut self) -> Option<&'a [T]> {
+		if self.index >= self.frame.samples() {
+			return None;
+		}
+
+		let channels = self.frame.channels() as usize;
+
+		let frame = unsafe {
+			slice::from_raw_parts(
+				mem::transmute::<_, *const T>((*self.frame.as_ptr()).data[0])
+					.offset((self.index * channels) as isize),
+				channels)
+		};
+
+		self.index += 1;
+
+		Some(frame)
+	}
+}
+
+pub struct ChannelSamples<'a, T: Sample> {
+	frame:   &'a Audio,
+	channel: usize,
+	index:   usize,
+	_marker: PhantomData<T>,
+}
+
+impl<'a, T: Sample + Copy> Iterator for ChannelSamples<'a, T> {
+	type Item = T;
+
+	fn next(&mut self) -> Option<T> {
+		if self.index >= self.frame.samples() {
+			return None;
+		}
+
+		let channels = self.frame.channels() as usize;
+
+		let value = unsafe {
+			let base = if self.frame.is_planar() {
+				mem::transmute::<_, *const T>((*self.frame.as_ptr()).data[self.channel])
+					.offset(self.index as isize)
+			}
+			else {
+				mem::transmute::<_, *const T>((*self.frame.as_ptr()).data[0])
+					.offset((self.index * channels + self.channel) as isize)
+			};
+
+			*base
+		};
+
+		self.index += 1;
+
+		Some(value)
+	}
+}
+
+fn packed(format: format::Sample) -> format::Sample {
+	use ::util::format::sample::Type::Packed;
+
+	match format {
+		format::Sample::U8(..)  => format::Sample::U8(Packed),
+		format::Sample::I16(..) => format::Sample::I16(Packed),
+		format::Sample::I32(..) => format::Sample::I32(Packed),
+		format::Sample::F32(..) => format::Sample::F32(Packed),
+		format::Sample::F64(..) => format::Sample::F64(Packed),
+		format::Sample::None    => format::Sample::None,
+	}
+}
+
+fn planar(format: format::Sample) -> format::Sample {
+	use ::util::format::sample::Type::Planar;
+
+	match format {
+		format::Sample::U8(..)  => format::Sample::U8(Planar),
+		format::Sample::I16(..) => format::Sample::I16(Planar),
+		format::Sample::I32(..) => format::Sample::I32(Planar),
+		format::Sample::F32(..) => format::Sample::F32(Planar),
+		format::Sample::F64(..) => format::Sample::F64(Planar),
+		format::Sample::None    => format::Sample::None,
+	}
+}
+
 pub trait Sample {
 	fn is_valid(format: format::Sample) -> bool;
 }
@@ -278,3 +662,84 @@ impl Sample for f64 {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::Audio;
+	use ::util::format;
+	use ::util::format::sample::Type::{Packed, Planar};
+	use ::ChannelLayout;
+
+	fn planar() -> Audio {
+		Audio::new(format::Sample::F32(Planar), 4, ChannelLayout::STEREO)
+	}
+
+	fn packed() -> Audio {
+		Audio::new(format::Sample::F32(Packed), 4, ChannelLayout::STEREO)
+	}
+
+	#[test]
+	fn plane_lengths() {
+		let frame = planar();
+		assert_eq!(frame.plane_count(), 2);
+		assert_eq!(frame.plane::<f32>(0).len(), 4);
+		assert_eq!(frame.plane::<f32>(1).len(), 4);
+		assert_eq!(frame.channel::<f32>(0).len(), 4);
+		assert_eq!(frame.channel::<f32>(1).len(), 4);
+	}
+
+	#[test]
+	fn packed_plane_length() {
+		let frame = packed();
+		assert_eq!(frame.plane_count(), 1);
+		assert_eq!(frame.plane::<f32>(0).len(), 8);
+	}
+
+	#[test]
+	#[should_panic]
+	fn channel_rejects_packed() {
+		packed().channel::<f32>(0);
+	}
+
+	#[test]
+	fn packed_iterators() {
+		let frame = packed();
+
+		let frames: Vec<_> = frame.frames::<f32>().unwrap().collect();
+		assert_eq!(frames.len(), 4);
+		assert!(frames.iter().all(|f| f.len() == 2));
+
+		assert_eq!(frame.channel_samples::<f32>(1).count(), 4);
+	}
+
+	#[test]
+	fn planar_channel_samples() {
+		assert_eq!(planar().channel_samples::<f32>(0).count(), 4);
+	}
+
+	#[test]
+	fn frames_rejects_planar() {
+		assert!(planar().frames::<f32>().is_err());
+	}
+
+	#[test]
+	fn planar_packed_round_trip() {
+		let mut frame = planar();
+
+		{
+			let mut planes = frame.planes_mut::<f32>();
+
+			for c in 0 .. planes.len() {
+				for n in 0 .. planes[c].len() {
+					planes[c][n] = (c * 10 + n) as f32;
+				}
+			}
+		}
+
+		let round = frame.to_packed().to_planar();
+
+		for c in 0 .. frame.channels() as usize {
+			assert_eq!(frame.channel::<f32>(c), round.channel::<f32>(c));
+		}
+	}
+}